@@ -1,8 +1,17 @@
 //! The automated teller machine gives you cash after you swipe your card and enter your pin.
-//! The atm may fail to give you cash if it is empty or you haven't swiped your card, or you have
-//! entered the wrong pin.
+//! The atm may fail to give you cash if it is empty, you haven't swiped your card, you have
+//! entered the wrong pin, or your account doesn't have the funds to cover the withdrawal.
 
 use crate::traits::{hash, StateMachine};
+use std::collections::HashMap;
+
+/// How many wrong PIN attempts a card gets before it is locked. Mirrors the
+/// retry counter smartcards (OpenPGP, CTAP2, ...) keep alongside the PIN
+/// itself.
+const PIN_ATTEMPTS: u8 = 3;
+
+/// Identifies the card (and therefore the account) a session belongs to.
+pub type CardId = u64;
 
 /// The keys on the ATM keypad
 #[derive(Hash, Debug, PartialEq, Eq, Clone)]
@@ -15,12 +24,25 @@ pub enum Key {
 }
 
 /// Something you can do to the ATM
+#[derive(Clone)]
 pub enum Action {
-    /// Swipe your card at the ATM. The attached value is the hash of the pin
-    /// that should be keyed in on the keypad next.
-    SwipeCard(u64),
+    /// Swipe your card at the ATM. The first value identifies the card, the
+    /// second is a fresh per-session salt agreed with the card for this
+    /// swipe, and the third is `hash(salt, pin)` for the pin that should be
+    /// keyed in on the keypad next.
+    SwipeCard(CardId, u64, u64),
     /// Press a key on the keypad
     PressKey(Key),
+    /// Administrative unlock. Clears a `Locked` card and returns the ATM to
+    /// `Auth::Waiting` regardless of its current state.
+    Reset,
+    /// Credit a card's account balance without needing a PIN session, the
+    /// way a deposit slot would.
+    Deposit(CardId, u64),
+    /// Abort an in-progress session and return the card. Clears any keyed-in
+    /// digits and returns the ATM to `Auth::Waiting`. A no-op while the ATM
+    /// is `Waiting`, and does not unlock a `Locked` card.
+    Cancel,
 }
 
 /// The various states of authentication possible with the ATM
@@ -28,12 +50,22 @@ pub enum Action {
 pub enum Auth {
     /// No session has begun yet. Waiting for the user to swipe their card
     Waiting,
-    /// The user has swiped their card, providing the enclosed PIN hash.
-    /// Waiting for the user to key in their pin
-    Authenticating(u64),
-    /// The user has authenticated. Waiting for them to key in the amount
-    /// of cash to withdraw
-    Authenticated,
+    /// The user has swiped `card`, providing the enclosed salted PIN hash
+    /// and the salt it was computed with. Waiting for the user to key in
+    /// their pin. `remaining` counts the wrong-PIN attempts left before the
+    /// card is locked.
+    Authenticating {
+        card: CardId,
+        salt: u64,
+        pin_hash: u64,
+        remaining: u8,
+    },
+    /// The user has authenticated as `card`. Waiting for them to key in the
+    /// amount of cash to withdraw
+    Authenticated { card: CardId },
+    /// The card has used up all its PIN attempts and is locked. No further
+    /// `SwipeCard` or `PressKey` actions are accepted until a `Reset`.
+    Locked,
 }
 
 /// The ATM. When a card is swiped, the ATM learns the correct pin's hash.
@@ -41,32 +73,53 @@ pub enum Auth {
 /// you like followed by enter. If the pin is incorrect, your card is returned
 /// and the ATM automatically goes back to the main menu. If your pin is correct,
 /// the ATM waits for you to key in an amount of money to withdraw. Withdraws
-/// are bounded only by the cash in the machine (there is no account balance).
-#[derive(PartialEq, Debug)]
+/// are bounded by both the cash in the machine and the authenticated card's
+/// account balance, and debit both. After `PIN_ATTEMPTS` wrong guesses in a
+/// row the card is locked and must be cleared with an administrative
+/// `Action::Reset`.
+#[derive(PartialEq, Debug, Clone)]
 pub struct Atm {
     /// How much money is in the ATM
-    cash_inside: u64,
+    pub(crate) cash_inside: u64,
     /// The machine's authentication status.
-    expected_pin_hash: Auth,
+    pub(crate) expected_pin_hash: Auth,
     /// All the keys that have been pressed since the last `Enter`
-    keystroke_register: Vec<Key>,
+    pub(crate) keystroke_register: Vec<Key>,
+    /// Each card's account balance, keyed by card identity.
+    pub(crate) balances: HashMap<CardId, u64>,
 }
 
 impl Atm {
-    fn swip_card(&mut self, hash_pin: u64) {
+    /// Build a fresh, unauthenticated ATM stocked with `cash_inside` and no
+    /// account balances on file.
+    pub fn new(cash_inside: u64) -> Self {
+        Atm {
+            cash_inside,
+            expected_pin_hash: Auth::Waiting,
+            keystroke_register: Vec::new(),
+            balances: HashMap::new(),
+        }
+    }
+
+    fn swip_card(&mut self, card: CardId, salt: u64, salted_pin_hash: u64) {
         if self.expected_pin_hash != Auth::Waiting {
             print!("wrong authenticate state");
             return;
         }
-        self.expected_pin_hash = Auth::Authenticating(hash_pin.clone());
+        self.expected_pin_hash = Auth::Authenticating {
+            card,
+            salt,
+            pin_hash: salted_pin_hash,
+            remaining: PIN_ATTEMPTS,
+        };
     }
 
     fn key_press(&mut self, key: Key) {
         match self.expected_pin_hash {
-            Auth::Authenticating(_) => {
+            Auth::Authenticating { .. } => {
                 self.pin_check(key);
             }
-            Auth::Authenticated => self.withdraw(key),
+            Auth::Authenticated { card } => self.withdraw(key, card),
             _ => {
                 print!("wrong authenticate state")
             }
@@ -74,28 +127,47 @@ impl Atm {
     }
 
     fn pin_check(&mut self, key: Key) {
+        let (card, salt, expected_pin_hash, remaining) = match self.expected_pin_hash {
+            Auth::Authenticating {
+                card,
+                salt,
+                pin_hash,
+                remaining,
+            } => (card, salt, pin_hash, remaining),
+            _ => return,
+        };
         match key {
             Key::Enter => {
-                let pin_hash = crate::traits::hash(&self.keystroke_register);
-                let expected_pin_hash = &self.expected_pin_hash;
-                if &Auth::Authenticating(pin_hash) == expected_pin_hash {
-                    self.expected_pin_hash = Auth::Authenticated;
-                    self.keystroke_register.clear();
+                let pin_hash = crate::traits::hash(salt, &self.keystroke_register);
+                self.keystroke_register.clear();
+                if pin_hash == expected_pin_hash {
+                    self.expected_pin_hash = Auth::Authenticated { card };
                     return;
                 }
-                self.keystroke_register.clear();
-                self.expected_pin_hash = Auth::default();
+                let remaining = remaining - 1;
+                self.expected_pin_hash = if remaining == 0 {
+                    Auth::Locked
+                } else {
+                    Auth::Authenticating {
+                        card,
+                        salt,
+                        pin_hash: expected_pin_hash,
+                        remaining,
+                    }
+                };
             }
             _ => self.keystroke_register.push(key),
         }
     }
 
-    fn withdraw(&mut self, key: Key) {
+    fn withdraw(&mut self, key: Key, card: CardId) {
         match key {
             Key::Enter => {
                 let with_draw_value = keys_into_u64(&self.keystroke_register);
-                if self.cash_inside >= with_draw_value {
+                let balance = *self.balances.get(&card).unwrap_or(&0);
+                if self.cash_inside >= with_draw_value && balance >= with_draw_value {
                     self.cash_inside -= with_draw_value;
+                    self.balances.insert(card, balance - with_draw_value);
                 }
                 self.expected_pin_hash = Auth::default();
                 self.keystroke_register.clear();
@@ -104,6 +176,25 @@ impl Atm {
             _ => self.keystroke_register.push(key),
         }
     }
+
+    fn deposit(&mut self, card: CardId, amount: u64) {
+        *self.balances.entry(card).or_insert(0) += amount;
+    }
+
+    fn reset(&mut self) {
+        self.expected_pin_hash = Auth::Waiting;
+        self.keystroke_register.clear();
+    }
+
+    fn cancel(&mut self) {
+        match self.expected_pin_hash {
+            Auth::Authenticating { .. } | Auth::Authenticated { .. } => {
+                self.keystroke_register.clear();
+                self.expected_pin_hash = Auth::Waiting;
+            }
+            _ => {}
+        }
+    }
 }
 
 // Implement trait Default for Auth
@@ -182,17 +273,27 @@ impl StateMachine for Atm {
             cash_inside: starting_state.cash_inside,
             expected_pin_hash: starting_state.expected_pin_hash.clone(),
             keystroke_register: Vec::new(),
+            balances: starting_state.balances.clone(),
         };
         atm.keystroke_register = vec![Key::One; starting_state.keystroke_register.len()];
         atm.keystroke_register
             .clone_from_slice(&starting_state.keystroke_register);
         match t {
-            Action::SwipeCard(value) => {
-                atm.swip_card(*value);
+            Action::SwipeCard(card, salt, salted_pin_hash) => {
+                atm.swip_card(*card, *salt, *salted_pin_hash);
             }
             Action::PressKey(value) => {
                 atm.key_press(value.clone());
             }
+            Action::Reset => {
+                atm.reset();
+            }
+            Action::Deposit(card, amount) => {
+                atm.deposit(*card, *amount);
+            }
+            Action::Cancel => {
+                atm.cancel();
+            }
         }
         atm
     }
@@ -204,12 +305,19 @@ fn sm_3_simple_swipe_card() {
         cash_inside: 10,
         expected_pin_hash: Auth::Waiting,
         keystroke_register: Vec::new(),
+        balances: HashMap::new(),
     };
-    let end = Atm::next_state(&start, &Action::SwipeCard(1234));
+    let end = Atm::next_state(&start, &Action::SwipeCard(1, 99, 1234));
     let expected = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating {
+            card: 1,
+            salt: 99,
+            pin_hash: 1234,
+            remaining: 3,
+        },
         keystroke_register: Vec::new(),
+        balances: HashMap::new(),
     };
 
     assert_eq!(end, expected);
@@ -219,28 +327,52 @@ fn sm_3_simple_swipe_card() {
 fn sm_3_swipe_card_again_part_way_through() {
     let start = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating {
+            card: 1,
+            salt: 99,
+            pin_hash: 1234,
+            remaining: 3,
+        },
         keystroke_register: Vec::new(),
+        balances: HashMap::new(),
     };
-    let end = Atm::next_state(&start, &Action::SwipeCard(1234));
+    let end = Atm::next_state(&start, &Action::SwipeCard(1, 99, 1234));
     let expected = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating {
+            card: 1,
+            salt: 99,
+            pin_hash: 1234,
+            remaining: 3,
+        },
         keystroke_register: Vec::new(),
+        balances: HashMap::new(),
     };
 
     assert_eq!(end, expected);
 
     let start = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating {
+            card: 1,
+            salt: 99,
+            pin_hash: 1234,
+            remaining: 3,
+        },
         keystroke_register: vec![Key::One, Key::Three],
+        balances: HashMap::new(),
     };
-    let end = Atm::next_state(&start, &Action::SwipeCard(1234));
+    let end = Atm::next_state(&start, &Action::SwipeCard(1, 99, 1234));
     let expected = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating {
+            card: 1,
+            salt: 99,
+            pin_hash: 1234,
+            remaining: 3,
+        },
         keystroke_register: vec![Key::One, Key::Three],
+        balances: HashMap::new(),
     };
 
     assert_eq!(end, expected);
@@ -252,12 +384,14 @@ fn sm_3_press_key_before_card_swipe() {
         cash_inside: 10,
         expected_pin_hash: Auth::Waiting,
         keystroke_register: Vec::new(),
+        balances: HashMap::new(),
     };
     let end = Atm::next_state(&start, &Action::PressKey(Key::One));
     let expected = Atm {
         cash_inside: 10,
         expected_pin_hash: Auth::Waiting,
         keystroke_register: Vec::new(),
+        balances: HashMap::new(),
     };
 
     assert_eq!(end, expected);
@@ -267,28 +401,52 @@ fn sm_3_press_key_before_card_swipe() {
 fn sm_3_enter_single_digit_of_pin() {
     let start = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating {
+            card: 1,
+            salt: 99,
+            pin_hash: 1234,
+            remaining: 3,
+        },
         keystroke_register: Vec::new(),
+        balances: HashMap::new(),
     };
     let end = Atm::next_state(&start, &Action::PressKey(Key::One));
     let expected = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating {
+            card: 1,
+            salt: 99,
+            pin_hash: 1234,
+            remaining: 3,
+        },
         keystroke_register: vec![Key::One],
+        balances: HashMap::new(),
     };
 
     assert_eq!(end, expected);
 
     let start = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating {
+            card: 1,
+            salt: 99,
+            pin_hash: 1234,
+            remaining: 3,
+        },
         keystroke_register: vec![Key::One],
+        balances: HashMap::new(),
     };
     let end1 = Atm::next_state(&start, &Action::PressKey(Key::Two));
     let expected1 = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating {
+            card: 1,
+            salt: 99,
+            pin_hash: 1234,
+            remaining: 3,
+        },
         keystroke_register: vec![Key::One, Key::Two],
+        balances: HashMap::new(),
     };
 
     assert_eq!(end1, expected1);
@@ -296,20 +454,33 @@ fn sm_3_enter_single_digit_of_pin() {
 
 #[test]
 fn sm_3_enter_wrong_pin() {
-    // Create hash of pin
+    // Create the salted hash of pin for this session
+    let salt = 7;
     let pin = vec![Key::One, Key::Two, Key::Three, Key::Four];
-    let pin_hash = crate::traits::hash(&pin);
+    let pin_hash = crate::traits::hash(salt, &pin);
 
     let start = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(pin_hash),
+        expected_pin_hash: Auth::Authenticating {
+            card: 1,
+            salt,
+            pin_hash,
+            remaining: 3,
+        },
         keystroke_register: vec![Key::Three, Key::Three, Key::Three, Key::Three],
+        balances: HashMap::new(),
     };
     let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
     let expected = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Waiting,
+        expected_pin_hash: Auth::Authenticating {
+            card: 1,
+            salt,
+            pin_hash,
+            remaining: 2,
+        },
         keystroke_register: Vec::new(),
+        balances: HashMap::new(),
     };
 
     assert_eq!(end, expected);
@@ -317,20 +488,128 @@ fn sm_3_enter_wrong_pin() {
 
 #[test]
 fn sm_3_enter_correct_pin() {
-    // Create hash of pin
+    // Create the salted hash of pin for this session
+    let salt = 7;
     let pin = vec![Key::One, Key::Two, Key::Three, Key::Four];
-    let pin_hash = crate::traits::hash(&pin);
+    let pin_hash = crate::traits::hash(salt, &pin);
 
     let start = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(pin_hash),
+        expected_pin_hash: Auth::Authenticating {
+            card: 1,
+            salt,
+            pin_hash,
+            remaining: 3,
+        },
         keystroke_register: vec![Key::One, Key::Two, Key::Three, Key::Four],
+        balances: HashMap::new(),
+    };
+    let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
+    let expected = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Auth::Authenticated { card: 1 },
+        keystroke_register: Vec::new(),
+        balances: HashMap::new(),
+    };
+
+    assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_5_same_pin_under_different_salts_yields_different_verifiers() {
+    let pin = vec![Key::One, Key::Two, Key::Three, Key::Four];
+
+    let hash_with_salt_1 = crate::traits::hash(1, &pin);
+    let hash_with_salt_2 = crate::traits::hash(2, &pin);
+
+    assert_ne!(hash_with_salt_1, hash_with_salt_2);
+
+    // Each salt still authenticates correctly against its own session.
+    let start = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Auth::Authenticating {
+            card: 1,
+            salt: 2,
+            pin_hash: hash_with_salt_2,
+            remaining: 3,
+        },
+        keystroke_register: pin,
+        balances: HashMap::new(),
     };
     let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
+
+    assert_eq!(end.expected_pin_hash, Auth::Authenticated { card: 1 });
+}
+
+#[test]
+fn sm_4_pin_lockout_after_all_attempts_used() {
+    let salt = 7;
+    let pin = vec![Key::One, Key::Two, Key::Three, Key::Four];
+    let pin_hash = crate::traits::hash(salt, &pin);
+
+    let mut atm = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Auth::Authenticating {
+            card: 1,
+            salt,
+            pin_hash,
+            remaining: 3,
+        },
+        keystroke_register: Vec::new(),
+        balances: HashMap::new(),
+    };
+
+    for remaining in [2, 1] {
+        atm = Atm::next_state(&atm, &Action::PressKey(Key::Three));
+        atm = Atm::next_state(&atm, &Action::PressKey(Key::Three));
+        atm = Atm::next_state(&atm, &Action::PressKey(Key::Enter));
+        assert_eq!(
+            atm.expected_pin_hash,
+            Auth::Authenticating {
+                card: 1,
+                salt,
+                pin_hash,
+                remaining
+            }
+        );
+    }
+
+    atm = Atm::next_state(&atm, &Action::PressKey(Key::Three));
+    atm = Atm::next_state(&atm, &Action::PressKey(Key::Three));
+    atm = Atm::next_state(&atm, &Action::PressKey(Key::Enter));
+    assert_eq!(atm.expected_pin_hash, Auth::Locked);
+}
+
+#[test]
+fn sm_4_locked_atm_rejects_swipe_card_and_key_press() {
+    let start = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Auth::Locked,
+        keystroke_register: Vec::new(),
+        balances: HashMap::new(),
+    };
+
+    let end = Atm::next_state(&start, &Action::SwipeCard(1, 99, 1234));
+    assert_eq!(end.expected_pin_hash, Auth::Locked);
+
+    let end = Atm::next_state(&start, &Action::PressKey(Key::One));
+    assert_eq!(end.expected_pin_hash, Auth::Locked);
+}
+
+#[test]
+fn sm_4_reset_unlocks_atm() {
+    let start = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Auth::Locked,
+        keystroke_register: Vec::new(),
+        balances: HashMap::new(),
+    };
+    let end = Atm::next_state(&start, &Action::Reset);
     let expected = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticated,
+        expected_pin_hash: Auth::Waiting,
         keystroke_register: Vec::new(),
+        balances: HashMap::new(),
     };
 
     assert_eq!(end, expected);
@@ -340,28 +619,32 @@ fn sm_3_enter_correct_pin() {
 fn sm_3_enter_single_digit_of_withdraw_amount() {
     let start = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticated,
+        expected_pin_hash: Auth::Authenticated { card: 1 },
         keystroke_register: Vec::new(),
+        balances: HashMap::new(),
     };
     let end = Atm::next_state(&start, &Action::PressKey(Key::One));
     let expected = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticated,
+        expected_pin_hash: Auth::Authenticated { card: 1 },
         keystroke_register: vec![Key::One],
+        balances: HashMap::new(),
     };
 
     assert_eq!(end, expected);
 
     let start = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticated,
+        expected_pin_hash: Auth::Authenticated { card: 1 },
         keystroke_register: vec![Key::One],
+        balances: HashMap::new(),
     };
     let end1 = Atm::next_state(&start, &Action::PressKey(Key::Four));
     let expected1 = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticated,
+        expected_pin_hash: Auth::Authenticated { card: 1 },
         keystroke_register: vec![Key::One, Key::Four],
+        balances: HashMap::new(),
     };
 
     assert_eq!(end1, expected1);
@@ -371,14 +654,16 @@ fn sm_3_enter_single_digit_of_withdraw_amount() {
 fn sm_3_try_to_withdraw_too_much() {
     let start = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticated,
+        expected_pin_hash: Auth::Authenticated { card: 1 },
         keystroke_register: vec![Key::One, Key::Four],
+        balances: HashMap::from([(1, 50)]),
     };
     let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
     let expected = Atm {
         cash_inside: 10,
         expected_pin_hash: Auth::Waiting,
         keystroke_register: Vec::new(),
+        balances: HashMap::from([(1, 50)]),
     };
 
     assert_eq!(end, expected);
@@ -388,14 +673,16 @@ fn sm_3_try_to_withdraw_too_much() {
 fn sm_3_withdraw_acceptable_amount() {
     let start = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticated,
+        expected_pin_hash: Auth::Authenticated { card: 1 },
         keystroke_register: vec![Key::One],
+        balances: HashMap::from([(1, 50)]),
     };
     let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
     let expected = Atm {
         cash_inside: 9,
         expected_pin_hash: Auth::Waiting,
         keystroke_register: Vec::new(),
+        balances: HashMap::from([(1, 49)]),
     };
 
     assert_eq!(end, expected);
@@ -405,15 +692,143 @@ fn sm_3_withdraw_acceptable_amount() {
 fn sm_3_withdraw_unacceptable_amount() {
     let start = Atm {
         cash_inside: 0,
-        expected_pin_hash: Auth::Authenticated,
+        expected_pin_hash: Auth::Authenticated { card: 1 },
         keystroke_register: vec![Key::One],
+        balances: HashMap::from([(1, 50)]),
     };
     let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
     let expected = Atm {
         cash_inside: 0,
         expected_pin_hash: Auth::Waiting,
         keystroke_register: Vec::new(),
+        balances: HashMap::from([(1, 50)]),
+    };
+
+    assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_5_withdraw_rejected_when_account_balance_insufficient() {
+    let start = Atm {
+        cash_inside: 100,
+        expected_pin_hash: Auth::Authenticated { card: 1 },
+        keystroke_register: vec![Key::One, Key::Four],
+        balances: HashMap::from([(1, 5)]),
+    };
+    let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
+    let expected = Atm {
+        cash_inside: 100,
+        expected_pin_hash: Auth::Waiting,
+        keystroke_register: Vec::new(),
+        balances: HashMap::from([(1, 5)]),
+    };
+
+    assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_5_withdraw_debits_both_cash_inside_and_account_balance() {
+    let start = Atm {
+        cash_inside: 20,
+        expected_pin_hash: Auth::Authenticated { card: 1 },
+        keystroke_register: vec![Key::One],
+        balances: HashMap::from([(1, 15)]),
+    };
+    let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
+    let expected = Atm {
+        cash_inside: 19,
+        expected_pin_hash: Auth::Waiting,
+        keystroke_register: Vec::new(),
+        balances: HashMap::from([(1, 14)]),
+    };
+
+    assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_5_deposit_credits_account_balance() {
+    let start = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Auth::Waiting,
+        keystroke_register: Vec::new(),
+        balances: HashMap::from([(1, 5)]),
+    };
+    let end = Atm::next_state(&start, &Action::Deposit(1, 20));
+    let expected = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Auth::Waiting,
+        keystroke_register: Vec::new(),
+        balances: HashMap::from([(1, 25)]),
+    };
+
+    assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_6_cancel_while_waiting_is_noop() {
+    let start = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Auth::Waiting,
+        keystroke_register: Vec::new(),
+        balances: HashMap::new(),
+    };
+    let end = Atm::next_state(&start, &Action::Cancel);
+
+    assert_eq!(end, start);
+}
+
+#[test]
+fn sm_6_cancel_during_pin_entry() {
+    let start = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Auth::Authenticating {
+            card: 1,
+            salt: 99,
+            pin_hash: 1234,
+            remaining: 3,
+        },
+        keystroke_register: vec![Key::One, Key::Two],
+        balances: HashMap::new(),
+    };
+    let end = Atm::next_state(&start, &Action::Cancel);
+    let expected = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Auth::Waiting,
+        keystroke_register: Vec::new(),
+        balances: HashMap::new(),
+    };
+
+    assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_6_cancel_during_withdrawal_amount_entry() {
+    let start = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Auth::Authenticated { card: 1 },
+        keystroke_register: vec![Key::One, Key::Four],
+        balances: HashMap::from([(1, 50)]),
+    };
+    let end = Atm::next_state(&start, &Action::Cancel);
+    let expected = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Auth::Waiting,
+        keystroke_register: Vec::new(),
+        balances: HashMap::from([(1, 50)]),
     };
 
     assert_eq!(end, expected);
 }
+
+#[test]
+fn sm_6_cancel_does_not_unlock_a_locked_card() {
+    let start = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Auth::Locked,
+        keystroke_register: Vec::new(),
+        balances: HashMap::new(),
+    };
+    let end = Atm::next_state(&start, &Action::Cancel);
+
+    assert_eq!(end, start);
+}
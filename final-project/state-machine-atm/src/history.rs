@@ -0,0 +1,120 @@
+//! A generic transition-history ledger for any [`StateMachine`].
+//!
+//! Services modeled as state machines are often easier to reason about if,
+//! instead of keeping only the latest state, you record the whole ordered
+//! sequence of transitions applied to a genesis state. The current state can
+//! then always be *discerned* from that record, and the record itself can be
+//! replayed or rolled back.
+
+use crate::traits::StateMachine;
+
+/// Records every transition applied to a [`StateMachine`], so the machine's
+/// state can be reconstructed from genesis at any time, replayed, or rolled
+/// back to an earlier point.
+pub struct History<M: StateMachine>
+where
+    M::State: Clone,
+    M::Transition: Clone,
+{
+    /// The state the machine started in, before any transitions were applied.
+    genesis: M::State,
+    /// Every transition applied so far, in the order they were applied.
+    transitions: Vec<M::Transition>,
+    /// `genesis` folded through `transitions`, cached so callers don't pay
+    /// the cost of a full replay just to read the current state.
+    current: M::State,
+}
+
+impl<M: StateMachine> History<M>
+where
+    M::State: Clone,
+    M::Transition: Clone,
+{
+    /// Start a new history at `genesis`, with no transitions applied yet.
+    pub fn new(genesis: M::State) -> Self {
+        History {
+            current: genesis.clone(),
+            genesis,
+            transitions: Vec::new(),
+        }
+    }
+
+    /// The state obtained by folding every recorded transition over genesis.
+    pub fn current_state(&self) -> &M::State {
+        &self.current
+    }
+
+    /// Every transition recorded so far, in the order it was applied.
+    pub fn transitions(&self) -> &[M::Transition] {
+        &self.transitions
+    }
+
+    /// Apply a new transition: record it and fold it into the current state.
+    pub fn apply(&mut self, t: M::Transition) {
+        self.current = M::next_state(&self.current, &t);
+        self.transitions.push(t);
+    }
+
+    /// Recompute the state from genesis plus every recorded transition,
+    /// ignoring the cached current state. Useful for auditing that the
+    /// cached state hasn't drifted from the recorded history.
+    pub fn replay(&self) -> M::State {
+        self.transitions
+            .iter()
+            .fold(self.genesis.clone(), |state, t| M::next_state(&state, t))
+    }
+
+    /// Truncate the history to its first `n` transitions, discarding the
+    /// rest, and recompute the current state from genesis.
+    pub fn rollback(&mut self, n: usize) {
+        self.transitions.truncate(n);
+        self.current = self.replay();
+    }
+}
+
+#[test]
+fn history_replay_matches_current_state() {
+    use crate::atm::{Action, Atm, Auth, Key};
+
+    let genesis = Atm::new(10);
+    let mut history = History::<Atm>::new(genesis);
+
+    let pin = vec![Key::One, Key::Two, Key::Three, Key::Four];
+    let pin_hash = crate::traits::hash(99, &pin);
+
+    history.apply(Action::SwipeCard(1, 99, pin_hash));
+    history.apply(Action::PressKey(Key::One));
+    history.apply(Action::PressKey(Key::Two));
+    history.apply(Action::PressKey(Key::Three));
+    history.apply(Action::PressKey(Key::Four));
+    history.apply(Action::PressKey(Key::Enter));
+
+    assert_eq!(
+        history.current_state().expected_pin_hash,
+        Auth::Authenticated { card: 1 }
+    );
+    assert_eq!(history.replay(), *history.current_state());
+}
+
+#[test]
+fn history_rollback_undoes_later_transitions() {
+    use crate::atm::{Action, Atm, Key};
+
+    let genesis = Atm::new(10);
+    let mut history = History::<Atm>::new(genesis);
+
+    history.apply(Action::SwipeCard(1, 99, 1234));
+    history.apply(Action::PressKey(Key::One));
+    history.apply(Action::PressKey(Key::Two));
+
+    let after_two_digits = history.current_state().clone();
+    assert_eq!(after_two_digits.keystroke_register, vec![Key::One, Key::Two]);
+
+    history.apply(Action::PressKey(Key::Three));
+    history.apply(Action::PressKey(Key::Four));
+
+    history.rollback(3);
+
+    assert_eq!(*history.current_state(), after_two_digits);
+    assert_eq!(history.transitions().len(), 3);
+}
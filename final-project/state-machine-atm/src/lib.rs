@@ -0,0 +1,3 @@
+pub mod atm;
+pub mod history;
+pub mod traits;
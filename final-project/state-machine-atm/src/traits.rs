@@ -0,0 +1,30 @@
+//! The core abstraction used throughout this crate: a state machine is
+//! something with a starting state and a pure function that folds a
+//! transition into a new state.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// A state machine is anything with a state, and a function to transition
+/// between states.
+pub trait StateMachine {
+    /// The states that can be occupied by this machine.
+    type State;
+    /// The transitions that can be made between states.
+    type Transition;
+    /// Calculate the resulting state when this transition is applied to the given state.
+    /// The resulting state is returned directly; there is no separate error value.
+    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State;
+}
+
+/// Helper function to hash anything that is hashable, keyed with a `salt` so
+/// the same value hashes differently under different salts. This keeps a
+/// verifier from being reusable across sessions: observing one session's
+/// hash doesn't reveal another session's verifier for the same underlying
+/// value.
+pub fn hash<T: Hash>(salt: u64, t: &T) -> u64 {
+    let mut s = DefaultHasher::new();
+    salt.hash(&mut s);
+    t.hash(&mut s);
+    s.finish()
+}